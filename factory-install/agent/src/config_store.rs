@@ -0,0 +1,60 @@
+//! Persisted key/value configuration store, modelled on ARTIQ coremgmt's
+//! flat config keys (`ip`, `startup`, ...). Values are applied live by
+//! whoever reads them on their next poll; there is no restart involved.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+const CONFIG_FILE: &str = "/var/lib/lekiwi-agent/config.json";
+
+/// Overrides the `--server` CLI argument.
+pub const KEY_SERVER: &str = "server";
+/// Overrides the [`crate::POLL_INTERVAL_MS`] constant.
+pub const KEY_POLL_INTERVAL_MS: &str = "poll_interval_ms";
+/// Tranquility ratio used by [`crate::AgentState::tranquil_interval_ms`].
+pub const KEY_TRANQUILITY: &str = "tranquility";
+
+#[derive(Clone)]
+pub struct ConfigStore {
+    inner: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl ConfigStore {
+    pub async fn load() -> Self {
+        let inner = fs::read_to_string(CONFIG_FILE)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<String> {
+        self.inner.read().await.get(key).cloned()
+    }
+
+    pub async fn set(&self, key: String, value: String) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map = self.inner.write().await;
+        map.insert(key, value);
+        Self::persist(&map)
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let mut map = self.inner.write().await;
+        let prev = map.remove(key);
+        Self::persist(&map)?;
+        Ok(prev)
+    }
+
+    fn persist(map: &HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = Path::new(CONFIG_FILE).parent().unwrap();
+        fs::create_dir_all(dir)?;
+        fs::write(CONFIG_FILE, serde_json::to_string_pretty(map)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! Bounded on-disk time series of `DynamicInfo` samples, so a dashboard or
+//! the control server can pull local trends (a thermal spike, a reboot)
+//! after the fact without the agent having to stream continuously.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::DynamicInfo;
+
+const HISTORY_FILE: &str = "/var/lib/lekiwi-agent/history.jsonl";
+/// Ring-buffer cap: comfortably under the agent's [`crate::MAX_MEMORY_MB`]
+/// budget while still covering roughly a day of samples at the default
+/// 5s poll interval.
+const MAX_HISTORY_ENTRIES: usize = 20_000;
+/// How far past [`MAX_HISTORY_ENTRIES`] the file is allowed to grow before
+/// it's compacted back down, so a full-file rewrite happens once every
+/// [`COMPACT_SLACK`] appends instead of on every single one.
+const COMPACT_SLACK: usize = 1_000;
+
+/// In-memory line count, so [`append`] doesn't need to re-read the whole
+/// file on every call just to decide whether it's due for compaction.
+/// Lazily seeded from disk on first use and kept in sync from then on.
+static CACHED_LEN: OnceLock<AtomicUsize> = OnceLock::new();
+
+fn cached_len() -> &'static AtomicUsize {
+    CACHED_LEN.get_or_init(|| {
+        let on_disk = fs::read_to_string(HISTORY_FILE)
+            .map(|c| c.lines().count())
+            .unwrap_or(0);
+        AtomicUsize::new(on_disk)
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub bucket_end: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: usize,
+    /// `boot_id` of the last sample folded into this bucket, so a
+    /// dashboard can tell which boot a bucket belongs to.
+    pub boot_id: String,
+    /// True if the robot rebooted partway through this bucket's window
+    /// (i.e. `boot_id` changed between two consecutive samples in it),
+    /// so a reboot is visible in the series even after downsampling.
+    pub reboot: bool,
+}
+
+/// Appends one sample to the ring buffer. The file is only trimmed back
+/// down to [`MAX_HISTORY_ENTRIES`] once every [`COMPACT_SLACK`] entries
+/// past the cap, so a normal append is an O(1) file append rather than an
+/// O(n) read-rewrite of the whole history — callers run this on every
+/// metrics tick and shouldn't block on a multi-MB disk shuffle.
+pub fn append(sample: &DynamicInfo) {
+    let dir = Path::new(HISTORY_FILE).parent().unwrap();
+    if let Err(e) = fs::create_dir_all(dir) {
+        warn!("failed to create history dir: {}", e);
+        return;
+    }
+    let Ok(line) = serde_json::to_string(sample) else {
+        return;
+    };
+
+    if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(HISTORY_FILE) {
+        if writeln!(f, "{line}").is_ok() {
+            let len = cached_len().fetch_add(1, Ordering::Relaxed) + 1;
+            if len >= MAX_HISTORY_ENTRIES + COMPACT_SLACK {
+                compact();
+            }
+        }
+    }
+}
+
+/// Rewrites the history file keeping only the newest [`MAX_HISTORY_ENTRIES`]
+/// lines, and resets the cached length to match.
+fn compact() {
+    if let Ok(contents) = fs::read_to_string(HISTORY_FILE) {
+        let lines: Vec<&str> = contents.lines().collect();
+        let kept: Vec<&str> = lines
+            .iter()
+            .skip(lines.len().saturating_sub(MAX_HISTORY_ENTRIES))
+            .copied()
+            .collect();
+        let new_len = kept.len();
+        if fs::write(HISTORY_FILE, kept.join("\n") + "\n").is_ok() {
+            cached_len().store(new_len, Ordering::Relaxed);
+        }
+    }
+}
+
+fn field_value(sample: &DynamicInfo, field: &str) -> Option<f64> {
+    match field {
+        "cpu_usage_percent" => Some(sample.cpu_usage_percent as f64),
+        "memory_used_mb" => Some(sample.memory_used_mb as f64),
+        "memory_free_mb" => Some(sample.memory_free_mb as f64),
+        "disk_used_gb" => Some(sample.disk_used_gb),
+        "disk_free_gb" => Some(sample.disk_free_gb),
+        "temperature_celsius" => sample.temperature_celsius.map(|t| t as f64),
+        "network_rx_bytes" => Some(sample.network_rx_bytes as f64),
+        "network_tx_bytes" => Some(sample.network_tx_bytes as f64),
+        "process_count" => Some(sample.process_count as f64),
+        "effective_poll_interval_ms" => Some(sample.effective_poll_interval_ms as f64),
+        "uptime_seconds" => Some(sample.uptime_seconds as f64),
+        _ => None,
+    }
+}
+
+/// Loads every sample in `[from, to]`, downsamples to at most `max_points`
+/// evenly-spaced buckets, and returns the min/max/avg of `field` in each.
+/// An unrecognized field name comes back as an `Err`.
+pub fn query(
+    field: &str,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    max_points: usize,
+) -> Result<Vec<HistoryBucket>, String> {
+    let contents = fs::read_to_string(HISTORY_FILE).unwrap_or_default();
+    let samples: Vec<DynamicInfo> = contents
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect();
+
+    bucket_samples(&samples, field, from, to, max_points)
+}
+
+/// The actual downsampling math behind [`query`], kept separate so it can
+/// be exercised with in-memory samples instead of a real history file.
+fn bucket_samples(
+    samples: &[DynamicInfo],
+    field: &str,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    max_points: usize,
+) -> Result<Vec<HistoryBucket>, String> {
+    if samples.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !samples.iter().any(|s| field_value(s, field).is_some()) {
+        return Err(format!("unknown field '{field}'"));
+    }
+
+    let from = from.unwrap_or(samples.first().unwrap().timestamp);
+    let to = to.unwrap_or(samples.last().unwrap().timestamp);
+
+    let in_range: Vec<&DynamicInfo> = samples
+        .iter()
+        .filter(|s| s.timestamp >= from && s.timestamp <= to)
+        .collect();
+    if in_range.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let max_points = max_points.max(1);
+    let span_ms = (to - from).num_milliseconds().max(1);
+    let bucket_span_ms = (span_ms / max_points as i64).max(1);
+
+    let mut buckets: Vec<HistoryBucket> = Vec::new();
+    let mut prev_boot_id: Option<&str> = None;
+    for sample in in_range {
+        // Tracked over every in-range sample, regardless of whether it has
+        // `field`, so a reboot straddling two otherwise-empty samples for
+        // this field still shows up.
+        let is_reboot = prev_boot_id.is_some_and(|p| p != sample.boot_id);
+        prev_boot_id = Some(&sample.boot_id);
+
+        let Some(value) = field_value(sample, field) else {
+            continue;
+        };
+        let offset_ms = (sample.timestamp - from).num_milliseconds().max(0);
+        let bucket_index = (offset_ms / bucket_span_ms).min(max_points as i64 - 1);
+        let bucket_start = from + chrono::Duration::milliseconds(bucket_index * bucket_span_ms);
+        let bucket_end = bucket_start + chrono::Duration::milliseconds(bucket_span_ms);
+
+        match buckets.iter_mut().find(|b| b.bucket_start == bucket_start) {
+            Some(b) => {
+                b.min = b.min.min(value);
+                b.max = b.max.max(value);
+                b.avg = (b.avg * b.count as f64 + value) / (b.count + 1) as f64;
+                b.count += 1;
+                b.boot_id = sample.boot_id.clone();
+                b.reboot = b.reboot || is_reboot;
+            }
+            None => buckets.push(HistoryBucket {
+                bucket_start,
+                bucket_end,
+                min: value,
+                max: value,
+                avg: value,
+                count: 1,
+                boot_id: sample.boot_id.clone(),
+                reboot: is_reboot,
+            }),
+        }
+    }
+
+    buckets.sort_by_key(|b| b.bucket_start);
+    Ok(buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(ts: DateTime<Utc>, cpu: f32) -> DynamicInfo {
+        sample_with_boot(ts, cpu, "boot")
+    }
+
+    fn sample_with_boot(ts: DateTime<Utc>, cpu: f32, boot_id: &str) -> DynamicInfo {
+        DynamicInfo {
+            timestamp: ts,
+            uptime_seconds: 0,
+            cpu_usage_percent: cpu,
+            memory_used_mb: 0,
+            memory_free_mb: 0,
+            disk_used_gb: 0.0,
+            disk_free_gb: 0.0,
+            temperature_celsius: None,
+            network_rx_bytes: 0,
+            network_tx_bytes: 0,
+            video_active: false,
+            teleop_active: false,
+            servo_positions: None,
+            realsense_connected: None,
+            process_count: 0,
+            boot_id: boot_id.to_string(),
+            effective_poll_interval_ms: 0,
+        }
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let samples = vec![sample(t0, 1.0)];
+        let err = bucket_samples(&samples, "not_a_real_field", None, None, 10).unwrap_err();
+        assert!(err.contains("not_a_real_field"));
+    }
+
+    #[test]
+    fn empty_samples_yield_empty_buckets() {
+        let buckets = bucket_samples(&[], "cpu_usage_percent", None, None, 10).unwrap();
+        assert!(buckets.is_empty());
+    }
+
+    #[test]
+    fn samples_at_span_boundaries_land_in_first_and_last_bucket() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let t_end = t0 + chrono::Duration::milliseconds(1000);
+        let samples = vec![sample(t0, 1.0), sample(t_end, 2.0)];
+
+        let buckets = bucket_samples(&samples, "cpu_usage_percent", None, None, 10).unwrap();
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets.first().unwrap().bucket_start, t0);
+        assert_eq!(buckets.last().unwrap().min, 2.0);
+        assert_eq!(buckets.last().unwrap().max, 2.0);
+    }
+
+    #[test]
+    fn samples_within_one_bucket_are_aggregated() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let samples = vec![
+            sample(t0, 1.0),
+            sample(t0 + chrono::Duration::milliseconds(10), 3.0),
+            sample(t0 + chrono::Duration::milliseconds(20), 5.0),
+        ];
+
+        // A single-point max_points collapses the whole span into one bucket.
+        let buckets = bucket_samples(&samples, "cpu_usage_percent", None, None, 1).unwrap();
+
+        assert_eq!(buckets.len(), 1);
+        let b = &buckets[0];
+        assert_eq!(b.count, 3);
+        assert_eq!(b.min, 1.0);
+        assert_eq!(b.max, 5.0);
+        assert_eq!(b.avg, 3.0);
+    }
+
+    #[test]
+    fn explicit_from_to_narrows_the_range() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let samples = vec![
+            sample(t0, 1.0),
+            sample(t0 + chrono::Duration::seconds(10), 2.0),
+            sample(t0 + chrono::Duration::seconds(20), 3.0),
+        ];
+
+        let buckets = bucket_samples(
+            &samples,
+            "cpu_usage_percent",
+            Some(t0),
+            Some(t0 + chrono::Duration::seconds(10)),
+            10,
+        )
+        .unwrap();
+
+        let total: usize = buckets.iter().map(|b| b.count).sum();
+        assert_eq!(total, 2);
+    }
+
+    #[test]
+    fn reboot_mid_bucket_is_flagged() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let samples = vec![
+            sample_with_boot(t0, 1.0, "boot-a"),
+            sample_with_boot(t0 + chrono::Duration::milliseconds(10), 2.0, "boot-b"),
+        ];
+
+        // Collapse both samples into a single bucket so the reboot has to
+        // be carried on the bucket rather than just picked out by eye.
+        let buckets = bucket_samples(&samples, "cpu_usage_percent", None, None, 1).unwrap();
+
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets[0].reboot);
+        assert_eq!(buckets[0].boot_id, "boot-b");
+    }
+
+    #[test]
+    fn no_reboot_within_a_single_boot_is_not_flagged() {
+        let t0 = Utc.timestamp_opt(0, 0).unwrap();
+        let samples = vec![sample(t0, 1.0), sample(t0 + chrono::Duration::seconds(1), 2.0)];
+
+        let buckets = bucket_samples(&samples, "cpu_usage_percent", None, None, 1).unwrap();
+
+        assert_eq!(buckets.len(), 1);
+        assert!(!buckets[0].reboot);
+    }
+}
@@ -1,4 +1,9 @@
-use axum::{extract::State, routing::get, Json, Router};
+use axum::{
+    extract::{Path as AxumPath, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use serde::{Deserialize, Serialize};
@@ -6,19 +11,42 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use sysinfo::{System, SystemExt, CpuExt, DiskExt, NetworkExt};
 use tokio::sync::RwLock;
-use tokio::time;
 use tracing::{info, warn, error};
 use uuid::Uuid;
 
+mod comms;
+mod config_store;
+mod history;
+mod ota;
+mod servo;
+mod workers;
+
+use comms::ControlChannel;
+use config_store::ConfigStore;
+use servo::ServoController;
+use workers::{Worker, WorkerManager, WorkerState};
+
 const AGENT_VERSION: &str = "1.0.0";
 const STATE_FILE: &str = "/var/lib/lekiwi-agent/state.json";
 const SERVO_COUNT_LEKIWI: usize = 9;
 const POLL_INTERVAL_MS: u64 = 5000;
 const MAX_MEMORY_MB: usize = 50;
 
+/// Default tranquility ratio: sleep for `step_duration * tranquility`
+/// between collection cycles. 0 means run flat out; higher yields more.
+const DEFAULT_TRANQUILITY: f64 = 1.0;
+/// Never let tranquility throttling push the interval outside these bounds.
+const MIN_POLL_INTERVAL_MS: u64 = 1000;
+const MAX_POLL_INTERVAL_MS: u64 = 60_000;
+/// Above these, the robot is considered busy/hot and tranquility is biased
+/// upward so the agent yields more of the CPU it's competing for.
+const CPU_BUSY_THRESHOLD_PERCENT: f32 = 80.0;
+const TEMP_HOT_THRESHOLD_C: f32 = 70.0;
+const BIAS_TRANQUILITY_BUMP: f64 = 1.0;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -83,6 +111,10 @@ struct DynamicInfo {
     realsense_connected: Option<bool>,
     process_count: usize,
     boot_id: String,
+    /// The collection interval actually used to produce this sample, after
+    /// tranquility throttling. See [`AgentState::tranquil_interval_ms`].
+    #[serde(default)]
+    effective_poll_interval_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,12 +124,97 @@ struct RobotState {
     first_seen: DateTime<Utc>,
     last_boot: DateTime<Utc>,
     boot_count: u32,
+    #[serde(default)]
+    pending_update: Option<ota::PendingUpdate>,
 }
 
 #[derive(Clone)]
 struct AgentState {
     robot_state: Arc<RwLock<RobotState>>,
     args: Arc<Args>,
+    workers: WorkerManager,
+    config: ConfigStore,
+    servo: ServoController,
+}
+
+impl AgentState {
+    /// Control server endpoint, with any live `PUT /config/server` override
+    /// taking precedence over the `--server` CLI argument.
+    async fn effective_server(&self) -> String {
+        self.config
+            .get(config_store::KEY_SERVER)
+            .await
+            .unwrap_or_else(|| self.args.server.clone())
+    }
+
+    /// Collection/persist/heartbeat cadence, with any live
+    /// `PUT /config/poll_interval_ms` override taking precedence over the
+    /// [`POLL_INTERVAL_MS`] default. Clamped defensively to
+    /// [`MIN_POLL_INTERVAL_MS`, `MAX_POLL_INTERVAL_MS`] even though
+    /// [`validate_config_value`] already rejects out-of-range writes, since
+    /// `tokio::time::interval` panics on zero.
+    async fn effective_poll_interval_ms(&self) -> u64 {
+        let raw = self
+            .config
+            .get(config_store::KEY_POLL_INTERVAL_MS)
+            .await
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(POLL_INTERVAL_MS);
+        raw.clamp(MIN_POLL_INTERVAL_MS, MAX_POLL_INTERVAL_MS)
+    }
+
+    /// Computes the next collection interval from the tranquility ratio:
+    /// `max(step_duration, POLL_INTERVAL_MS) * tranquility`, rather than
+    /// ticking on a fixed timer. `collect_dynamic_info` normally finishes
+    /// in well under a second, so flooring the baseline at
+    /// [`POLL_INTERVAL_MS`] keeps the common case at the original steady
+    /// cadence instead of collapsing to [`MIN_POLL_INTERVAL_MS`]; an
+    /// unusually slow pass still pushes the interval out past that
+    /// baseline on its own. The ratio is biased upward when the robot is
+    /// busy or running hot, and the result is clamped to
+    /// [`MIN_POLL_INTERVAL_MS`, `MAX_POLL_INTERVAL_MS`].
+    async fn tranquil_interval_ms(
+        &self,
+        step_duration: Duration,
+        cpu_usage_percent: f32,
+        temperature_celsius: Option<f32>,
+    ) -> u64 {
+        let mut tranquility = self
+            .config
+            .get(config_store::KEY_TRANQUILITY)
+            .await
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_TRANQUILITY);
+
+        if cpu_usage_percent > CPU_BUSY_THRESHOLD_PERCENT {
+            tranquility += BIAS_TRANQUILITY_BUMP;
+        }
+        if temperature_celsius.is_some_and(|t| t > TEMP_HOT_THRESHOLD_C) {
+            tranquility += BIAS_TRANQUILITY_BUMP;
+        }
+
+        let baseline_ms = (step_duration.as_millis() as u64).max(POLL_INTERVAL_MS);
+        let raw_ms = (baseline_ms as f64 * tranquility) as u64;
+        raw_ms.clamp(MIN_POLL_INTERVAL_MS, MAX_POLL_INTERVAL_MS)
+    }
+}
+
+/// Validates a config write before it's persisted, so a bad value (a zero
+/// or absurd poll interval, say) can't be pushed live through
+/// `PUT /config/:key` or a remote `SetConfig` command and wedge a worker
+/// into a crash loop. Unrecognized keys are accepted as-is.
+fn validate_config_value(key: &str, value: &str) -> Result<(), String> {
+    if key == config_store::KEY_POLL_INTERVAL_MS {
+        let parsed: u64 = value
+            .parse()
+            .map_err(|_| format!("'{value}' is not a valid poll_interval_ms"))?;
+        if !(MIN_POLL_INTERVAL_MS..=MAX_POLL_INTERVAL_MS).contains(&parsed) {
+            return Err(format!(
+                "poll_interval_ms must be between {MIN_POLL_INTERVAL_MS} and {MAX_POLL_INTERVAL_MS}"
+            ));
+        }
+    }
+    Ok(())
 }
 
 impl RobotType {
@@ -184,17 +301,6 @@ fn check_teleop_active() -> bool {
         .unwrap_or(false)
 }
 
-fn get_servo_positions(robot_type: &RobotType) -> Option<Vec<i32>> {
-    match robot_type {
-        RobotType::Lekiwi => {
-            // Read servo positions via I2C
-            // This is a placeholder - actual implementation would use rppal or i2c-dev
-            Some(vec![0; SERVO_COUNT_LEKIWI])
-        }
-        _ => None,
-    }
-}
-
 async fn collect_system_info(robot_id: String) -> SystemInfo {
     let mut sys = System::new_all();
     sys.refresh_all();
@@ -239,7 +345,7 @@ async fn collect_system_info(robot_id: String) -> SystemInfo {
     }
 }
 
-async fn collect_dynamic_info(robot_type: &RobotType) -> DynamicInfo {
+async fn collect_dynamic_info(robot_type: &RobotType, servo: &ServoController) -> DynamicInfo {
     let mut sys = System::new_all();
     sys.refresh_all();
     
@@ -276,7 +382,11 @@ async fn collect_dynamic_info(robot_type: &RobotType) -> DynamicInfo {
         network_tx_bytes,
         video_active: check_video_active(),
         teleop_active: check_teleop_active(),
-        servo_positions: get_servo_positions(robot_type),
+        servo_positions: if matches!(robot_type, RobotType::Lekiwi) {
+            servo.read_positions().await
+        } else {
+            None
+        },
         realsense_connected: if matches!(robot_type, RobotType::XLE) {
             Some(Path::new("/dev/realsense2").exists())
         } else {
@@ -284,12 +394,15 @@ async fn collect_dynamic_info(robot_type: &RobotType) -> DynamicInfo {
         },
         process_count: sys.processes().len(),
         boot_id: get_boot_id(),
+        // Overwritten by `MetricsCollector::step` once it knows how long
+        // this very collection took; POLL_INTERVAL_MS is just a sane seed.
+        effective_poll_interval_ms: POLL_INTERVAL_MS,
     }
 }
 
-async fn load_or_create_state(robot_id: String) -> RobotState {
+async fn load_or_create_state(robot_id: String, servo: &ServoController) -> RobotState {
     let system_info = collect_system_info(robot_id).await;
-    let dynamic_info = collect_dynamic_info(&system_info.robot_type).await;
+    let dynamic_info = collect_dynamic_info(&system_info.robot_type, servo).await;
     
     if let Ok(contents) = fs::read_to_string(STATE_FILE) {
         if let Ok(mut state) = serde_json::from_str::<RobotState>(&contents) {
@@ -311,6 +424,7 @@ async fn load_or_create_state(robot_id: String) -> RobotState {
         first_seen: Utc::now(),
         last_boot: Utc::now(),
         boot_count: 1,
+        pending_update: None,
     }
 }
 
@@ -322,67 +436,102 @@ async fn save_state(state: &RobotState) -> Result<(), Box<dyn std::error::Error>
     Ok(())
 }
 
-async fn send_heartbeat(state: &RobotState, server: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(5))
-        .build()?;
-    
-    let _response = client
-        .post(format!("{}/heartbeat", server))
-        .json(state)
-        .send()
-        .await?;
-    
-    Ok(())
+/// Checks the agent's own RSS against [`MAX_MEMORY_MB`] and logs if it's over.
+fn check_memory_budget() {
+    let current_mem = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|s| {
+            s.lines()
+                .find(|line| line.starts_with("VmRSS:"))
+                .and_then(|line| {
+                    line.split_whitespace()
+                        .nth(1)
+                        .and_then(|s| s.parse::<usize>().ok())
+                })
+        })
+        .unwrap_or(0) / 1024; // Convert to MB
+
+    if current_mem > MAX_MEMORY_MB {
+        warn!("Memory usage {}MB exceeds limit {}MB", current_mem, MAX_MEMORY_MB);
+    }
 }
 
-async fn monitoring_loop(agent_state: AgentState) {
-    let mut interval = time::interval(Duration::from_millis(POLL_INTERVAL_MS));
-    
-    loop {
-        interval.tick().await;
-        
-        // Update dynamic info
-        let mut state = agent_state.robot_state.write().await;
-        let new_dynamic = collect_dynamic_info(&state.system_info.robot_type).await;
-        
-        // Check for reboot
+/// Polls `sysinfo` on an interval and writes the result into `robot_state`,
+/// bumping `boot_count` when the kernel's boot id changes underneath it.
+struct MetricsCollector {
+    agent_state: AgentState,
+}
+
+#[async_trait::async_trait]
+impl Worker for MetricsCollector {
+    fn name(&self) -> &str {
+        "metrics-collector"
+    }
+
+    async fn step(&mut self) -> WorkerState {
+        let step_start = Instant::now();
+
+        // Collected into a local first, without holding the write lock:
+        // collect_dynamic_info does real I2C transactions now, and readers
+        // like /status, the state persister, and the control channel
+        // shouldn't have to wait on a slow or stalled bus.
+        let robot_type = self.agent_state.robot_state.read().await.system_info.robot_type.clone();
+        let new_dynamic = collect_dynamic_info(&robot_type, &self.agent_state.servo).await;
+
+        let mut state = self.agent_state.robot_state.write().await;
         if state.dynamic_info.boot_id != new_dynamic.boot_id {
             state.last_boot = Utc::now();
             state.boot_count += 1;
             info!("Detected reboot, updating boot count to {}", state.boot_count);
         }
-        
+
         state.dynamic_info = new_dynamic;
-        
-        // Save state to disk
-        if let Err(e) = save_state(&state).await {
-            warn!("Failed to save state: {}", e);
-        }
-        
-        // Send heartbeat to server
-        if let Err(e) = send_heartbeat(&state, &agent_state.args.server).await {
-            warn!("Failed to send heartbeat: {}", e);
-        }
-        
-        drop(state); // Release write lock
-        
-        // Check memory usage and adjust if needed
-        let current_mem = std::fs::read_to_string("/proc/self/status")
-            .ok()
-            .and_then(|s| {
-                s.lines()
-                    .find(|line| line.starts_with("VmRSS:"))
-                    .and_then(|line| {
-                        line.split_whitespace()
-                            .nth(1)
-                            .and_then(|s| s.parse::<usize>().ok())
-                    })
-            })
-            .unwrap_or(0) / 1024; // Convert to MB
-        
-        if current_mem > MAX_MEMORY_MB {
-            warn!("Memory usage {}MB exceeds limit {}MB", current_mem, MAX_MEMORY_MB);
+
+        // Tranquility throttling: the slower this collection just was (and
+        // the busier/hotter the robot is), the longer we back off before
+        // the next one, instead of ticking on a fixed timer regardless.
+        let sleep_ms = self
+            .agent_state
+            .tranquil_interval_ms(
+                step_start.elapsed(),
+                state.dynamic_info.cpu_usage_percent,
+                state.dynamic_info.temperature_celsius,
+            )
+            .await;
+        state.dynamic_info.effective_poll_interval_ms = sleep_ms;
+        let sample = state.dynamic_info.clone();
+
+        drop(state);
+
+        history::append(&sample);
+
+        check_memory_budget();
+        tokio::time::sleep(Duration::from_millis(sleep_ms)).await;
+
+        WorkerState::Idle(Utc::now() + chrono::Duration::milliseconds(sleep_ms as i64))
+    }
+}
+
+/// Persists the latest `robot_state` to [`STATE_FILE`] on the same cadence
+/// as collection, independently of whether the heartbeat send succeeds.
+struct StatePersister {
+    agent_state: AgentState,
+}
+
+#[async_trait::async_trait]
+impl Worker for StatePersister {
+    fn name(&self) -> &str {
+        "state-persister"
+    }
+
+    async fn step(&mut self) -> WorkerState {
+        let interval_ms = self.agent_state.effective_poll_interval_ms().await;
+        tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+
+        let state = self.agent_state.robot_state.read().await;
+        match save_state(&state).await {
+            Ok(()) => WorkerState::Idle(Utc::now() + chrono::Duration::milliseconds(interval_ms as i64)),
+            Err(e) => WorkerState::Errored(format!("failed to save state: {e}")),
         }
     }
 }
@@ -393,10 +542,155 @@ async fn get_status(State(agent_state): State<AgentState>) -> Json<RobotState> {
     Json(state.clone())
 }
 
+async fn get_workers(State(agent_state): State<AgentState>) -> Json<Vec<workers::WorkerStatus>> {
+    Json(agent_state.workers.statuses().await)
+}
+
 async fn get_health() -> &'static str {
     "OK"
 }
 
+#[derive(Debug, Deserialize)]
+struct SetConfigRequest {
+    value: String,
+}
+
+async fn get_config_key(
+    State(agent_state): State<AgentState>,
+    AxumPath(key): AxumPath<String>,
+) -> Result<Json<String>, StatusCode> {
+    agent_state
+        .config
+        .get(&key)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn put_config_key(
+    State(agent_state): State<AgentState>,
+    AxumPath(key): AxumPath<String>,
+    Json(req): Json<SetConfigRequest>,
+) -> (StatusCode, String) {
+    if let Err(e) = validate_config_value(&key, &req.value) {
+        warn!("rejected config write: {}", e);
+        return (StatusCode::BAD_REQUEST, e);
+    }
+
+    match agent_state.config.set(key, req.value).await {
+        Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+        Err(e) => {
+            error!("failed to persist config: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        }
+    }
+}
+
+async fn delete_config_key(
+    State(agent_state): State<AgentState>,
+    AxumPath(key): AxumPath<String>,
+) -> StatusCode {
+    match agent_state.config.remove(&key).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("failed to persist config: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Write-only slot: accepts a new agent binary plus a detached signature,
+/// verifies and swaps it in, and restarts the process to apply it.
+async fn put_agent_update(
+    State(agent_state): State<AgentState>,
+    Json(req): Json<ota::AgentUpdateRequest>,
+) -> StatusCode {
+    match ota::stage_and_swap(&req) {
+        Ok(pending) => {
+            agent_state.robot_state.write().await.pending_update = Some(pending);
+            info!("agent update to {} staged, restarting to apply", req.version);
+            tokio::spawn(async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                std::process::exit(0);
+            });
+            StatusCode::ACCEPTED
+        }
+        Err(e) => {
+            error!("agent update rejected: {}", e);
+            StatusCode::BAD_REQUEST
+        }
+    }
+}
+
+/// Writes a remote servo command to the PCA9685, gated behind the
+/// `servo.injection_enabled` config flag and refused outright while an
+/// operator has teleop active, so a remote command can't fight them.
+async fn post_servo_inject(
+    State(agent_state): State<AgentState>,
+    Json(req): Json<servo::InjectRequest>,
+) -> (StatusCode, String) {
+    let enabled = agent_state
+        .config
+        .get(servo::KEY_INJECTION_ENABLED)
+        .await
+        .as_deref()
+        == Some("true");
+    if !enabled {
+        return (StatusCode::FORBIDDEN, "servo injection is disabled".to_string());
+    }
+
+    if agent_state.robot_state.read().await.dynamic_info.teleop_active {
+        return (
+            StatusCode::CONFLICT,
+            "teleop is active, refusing remote servo injection".to_string(),
+        );
+    }
+
+    let result = match req {
+        servo::InjectRequest::Single { channel, position } => {
+            agent_state.servo.inject(&agent_state.config, channel, position).await
+        }
+        servo::InjectRequest::Vector { positions } => {
+            let mut result = Ok(());
+            for (channel, position) in positions.into_iter().enumerate() {
+                result = agent_state.servo.inject(&agent_state.config, channel, position).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            result
+        }
+    };
+
+    match result {
+        Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQueryParams {
+    field: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    max_points: Option<usize>,
+}
+
+/// Returns `field` downsampled to at most `max_points` min/max/avg buckets
+/// over `[from, to]` (unix seconds, defaulting to the full local history).
+async fn get_history(
+    Query(params): Query<HistoryQueryParams>,
+) -> Result<Json<Vec<history::HistoryBucket>>, (StatusCode, String)> {
+    let from = params.from.and_then(|ts| DateTime::from_timestamp(ts, 0));
+    let to = params.to.and_then(|ts| DateTime::from_timestamp(ts, 0));
+    let max_points = params.max_points.unwrap_or(100);
+
+    history::query(&params.field, from, to, max_points)
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
@@ -411,28 +705,78 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         id
     });
     
+    // Roll back an unconfirmed OTA update from a previous boot before we
+    // do anything else, so a bad push can't compound into a worse state.
+    let reconciled_update = ota::reconcile_startup();
+
+    let servo = ServoController::open();
+
     // Load or create initial state
-    let initial_state = load_or_create_state(robot_id.clone()).await;
+    let mut initial_state = load_or_create_state(robot_id.clone(), &servo).await;
+    initial_state.pending_update = reconciled_update;
     info!("Robot type detected: {:?}", initial_state.system_info.robot_type);
     info!("Robot ID: {}", initial_state.system_info.robot_id);
-    
+
     // Save initial state
     save_state(&initial_state).await?;
-    
+
+    let config = ConfigStore::load().await;
+    let pending_update = initial_state.pending_update.clone();
+
     let agent_state = AgentState {
         robot_state: Arc::new(RwLock::new(initial_state)),
         args: Arc::new(args.clone()),
+        workers: WorkerManager::new(),
+        config,
+        servo,
     };
-    
-    // Start monitoring loop
-    let monitor_state = agent_state.clone();
-    tokio::spawn(async move {
-        monitoring_loop(monitor_state).await;
-    });
-    
+
+    if pending_update.is_some() {
+        // Give the freshly swapped binary a chance to prove it's healthy
+        // before clearing the marker a future boot's reconcile checks. A
+        // binary that's merely still scheduled but never actually bound
+        // its listener shouldn't get waved through, so this really calls
+        // /health rather than just trusting the process stayed alive.
+        let confirm_state = agent_state.clone();
+        let port = args.port;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(ota::HEALTH_CHECK_GRACE_SECS)).await;
+            if ota::check_health(port).await {
+                ota::confirm_update_healthy();
+                confirm_state.robot_state.write().await.pending_update = None;
+            } else {
+                error!("OTA update failed its /health check, restarting to roll back");
+                std::process::exit(1);
+            }
+        });
+    }
+
+    // Spawn each background duty as an independently supervised worker,
+    // rather than one monolithic loop that takes all three down together.
+    agent_state.workers.spawn(Box::new(MetricsCollector {
+        agent_state: agent_state.clone(),
+    }));
+    agent_state.workers.spawn(Box::new(StatePersister {
+        agent_state: agent_state.clone(),
+    }));
+    agent_state
+        .workers
+        .spawn(Box::new(ControlChannel::new(agent_state.clone())));
+
     // Start local API server
     let app = Router::new()
         .route("/status", get(get_status))
+        .route("/workers", get(get_workers))
+        .route(
+            "/config/agent",
+            axum::routing::put(put_agent_update),
+        )
+        .route(
+            "/config/:key",
+            get(get_config_key).put(put_config_key).delete(delete_config_key),
+        )
+        .route("/servo/inject", axum::routing::post(post_servo_inject))
+        .route("/history", get(get_history))
         .route("/health", get(get_health))
         .with_state(agent_state);
     
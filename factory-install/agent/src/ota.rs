@@ -0,0 +1,167 @@
+//! Signed over-the-air self-update.
+//!
+//! Modelled on ARTIQ coremgmt's write-only `boot` config slot: a new agent
+//! binary plus a detached signature arrives over `PUT /config/agent`, gets
+//! verified against a pinned public key, staged to a temp path, and
+//! atomically swapped in. If a swap is never confirmed healthy by the time
+//! a fresh one would be expected, the next boot restores the previous
+//! binary instead of leaving a robot bricked in the field.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+/// Public half of the release signing key, checked in as
+/// `keys/ota_release_pub.bin` and compiled straight into the binary. The
+/// matching private key lives only with whoever signs releases and is
+/// never committed here, so there's no runtime path that can accept an
+/// update signed by anything else.
+const OTA_PUBLIC_KEY: [u8; 32] = *include_bytes!("../keys/ota_release_pub.bin");
+
+const CURRENT_BINARY: &str = "/usr/local/bin/lekiwi-agent";
+const STAGED_BINARY: &str = "/var/lib/lekiwi-agent/agent.staged";
+const BACKUP_BINARY: &str = "/var/lib/lekiwi-agent/agent.backup";
+const UPDATE_MARKER: &str = "/var/lib/lekiwi-agent/update.pending";
+
+/// How long a freshly swapped binary has to come up and serve `/health`
+/// before an unconfirmed marker is treated as a failed push.
+pub const HEALTH_CHECK_GRACE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUpdate {
+    pub version: String,
+    pub staged_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentUpdateRequest {
+    pub version: String,
+    pub binary_base64: String,
+    pub signature_base64: String,
+}
+
+#[derive(Debug)]
+pub enum OtaError {
+    BadSignature,
+    Io(std::io::Error),
+    Encoding(String),
+}
+
+impl std::fmt::Display for OtaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OtaError::BadSignature => write!(f, "signature verification failed"),
+            OtaError::Io(e) => write!(f, "io error: {e}"),
+            OtaError::Encoding(e) => write!(f, "encoding error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for OtaError {}
+
+fn verify(binary: &[u8], signature: &[u8]) -> Result<(), OtaError> {
+    let key = VerifyingKey::from_bytes(&OTA_PUBLIC_KEY).map_err(|_| OtaError::BadSignature)?;
+    let sig = Signature::from_slice(signature).map_err(|_| OtaError::BadSignature)?;
+    // `verify_strict` rejects the cofactor/malleability weaknesses plain
+    // `verify` lets through (including degenerate identity-key forgeries),
+    // which matters for a check gating what code this agent will run.
+    key.verify_strict(binary, &sig)
+        .map_err(|_| OtaError::BadSignature)
+}
+
+/// Verify, stage, and atomically swap in a new agent binary, keeping a
+/// backup of the binary it replaces so [`reconcile_startup`] can roll
+/// back a bad push.
+pub fn stage_and_swap(req: &AgentUpdateRequest) -> Result<PendingUpdate, OtaError> {
+    let binary = base64::decode(&req.binary_base64)
+        .map_err(|e| OtaError::Encoding(e.to_string()))?;
+    let signature = base64::decode(&req.signature_base64)
+        .map_err(|e| OtaError::Encoding(e.to_string()))?;
+
+    verify(&binary, &signature)?;
+
+    let dir = Path::new(STAGED_BINARY).parent().unwrap();
+    fs::create_dir_all(dir).map_err(OtaError::Io)?;
+    fs::write(STAGED_BINARY, &binary).map_err(OtaError::Io)?;
+    fs::set_permissions(STAGED_BINARY, fs::Permissions::from_mode(0o755)).map_err(OtaError::Io)?;
+
+    if Path::new(CURRENT_BINARY).exists() {
+        fs::copy(CURRENT_BINARY, BACKUP_BINARY).map_err(OtaError::Io)?;
+    }
+    fs::rename(STAGED_BINARY, CURRENT_BINARY).map_err(OtaError::Io)?;
+
+    let pending = PendingUpdate {
+        version: req.version.clone(),
+        staged_at: Utc::now(),
+    };
+    fs::write(UPDATE_MARKER, serde_json::to_string(&pending).unwrap()).map_err(OtaError::Io)?;
+
+    info!("staged and swapped agent binary to version {}", req.version);
+    Ok(pending)
+}
+
+/// Called once at startup. A marker left over from a swap that's still
+/// within its grace window means this boot is the one that should confirm
+/// it; a marker older than the grace window means a previous boot already
+/// had its chance and never confirmed, so roll back to the backup binary.
+/// Returns the pending update if this boot should still confirm it.
+pub fn reconcile_startup() -> Option<PendingUpdate> {
+    let marker = fs::read_to_string(UPDATE_MARKER).ok()?;
+    let pending: PendingUpdate = serde_json::from_str(&marker).ok()?;
+
+    let age = Utc::now() - pending.staged_at;
+    if age > chrono::Duration::seconds(HEALTH_CHECK_GRACE_SECS as i64) {
+        warn!(
+            "OTA update to {} never confirmed healthy, rolling back",
+            pending.version
+        );
+        if Path::new(BACKUP_BINARY).exists() {
+            if let Err(e) = fs::copy(BACKUP_BINARY, CURRENT_BINARY) {
+                error!("failed to roll back agent binary: {}", e);
+            }
+        }
+        let _ = fs::remove_file(UPDATE_MARKER);
+        return None;
+    }
+
+    Some(pending)
+}
+
+/// Called once the agent has been up through the grace window and
+/// [`check_health`] has confirmed it, clearing the marker so this boot
+/// isn't rolled back later.
+pub fn confirm_update_healthy() {
+    if Path::new(UPDATE_MARKER).exists() {
+        let _ = fs::remove_file(UPDATE_MARKER);
+        info!("confirmed OTA update healthy");
+    }
+}
+
+/// Actually calls the agent's own `/health` route over a plain TCP
+/// connection, so a process that's merely still scheduled (but whose
+/// listener task panicked, or never bound) doesn't get waved through as
+/// healthy. No extra HTTP client dependency needed for one GET.
+pub async fn check_health(port: u16) -> bool {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await else {
+        return false;
+    };
+    let request =
+        format!("GET /health HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\nConnection: close\r\n\r\n");
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+
+    let mut response = String::new();
+    if stream.read_to_string(&mut response).await.is_err() {
+        return false;
+    }
+    response.starts_with("HTTP/1.1 200") || response.starts_with("HTTP/1.0 200")
+}
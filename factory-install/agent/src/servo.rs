@@ -0,0 +1,189 @@
+//! Real servo telemetry and remote position injection for the PCA9685
+//! PWM controller that drives Lekiwi's 9 servos.
+
+use std::sync::Arc;
+
+use rppal::i2c::I2c;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config_store::ConfigStore;
+use crate::SERVO_COUNT_LEKIWI;
+
+const PCA9685_ADDRESS: u16 = 0x40;
+const I2C_BUS: u8 = 1;
+
+const REG_MODE1: u8 = 0x00;
+const REG_LED0_ON_L: u8 = 0x06;
+const BYTES_PER_CHANNEL: u8 = 4;
+
+/// Gates `POST /servo/inject`; defaults to off so a remote command can't
+/// actuate a robot nobody's standing in front of.
+pub const KEY_INJECTION_ENABLED: &str = "servo.injection_enabled";
+
+/// Conservative PWM bounds used when a channel has no configured min/max.
+const DEFAULT_MIN_PWM: i32 = 102; // ~2.5% duty cycle
+const DEFAULT_MAX_PWM: i32 = 512; // ~12.5% duty cycle
+
+fn key_min(channel: usize) -> String {
+    format!("servo.{channel}.min")
+}
+
+fn key_max(channel: usize) -> String {
+    format!("servo.{channel}.max")
+}
+
+/// `POST /servo/inject` body: either a single channel/position pair or a
+/// full 9-element vector applied channel-by-channel in order.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum InjectRequest {
+    Single { channel: usize, position: i32 },
+    Vector { positions: Vec<i32> },
+}
+
+/// Shared handle to the PCA9685. I2C transactions go through a mutex since
+/// they aren't safe to interleave across concurrent readers/writers.
+#[derive(Clone)]
+pub struct ServoController {
+    bus: Arc<Mutex<Option<I2c>>>,
+}
+
+impl ServoController {
+    /// Opens the I2C bus and wakes the PCA9685. Absent hardware (XLE
+    /// robots, bring-up benches) is not an error: telemetry and injection
+    /// just report/reject as unavailable.
+    pub fn open() -> Self {
+        let bus = I2c::with_bus(I2C_BUS)
+            .and_then(|mut bus| {
+                bus.set_slave_address(PCA9685_ADDRESS)?;
+                bus.smbus_write_byte(REG_MODE1, 0x00)?;
+                Ok(bus)
+            })
+            .map_err(|e| warn!("PCA9685 not available on i2c-{}: {}", I2C_BUS, e))
+            .ok();
+
+        Self {
+            bus: Arc::new(Mutex::new(bus)),
+        }
+    }
+
+    /// Reads the live ON/OFF PWM window for each of the 9 channels. Returns
+    /// `None` if the controller isn't present or a read fails partway.
+    ///
+    /// The actual I2C transactions are synchronous syscalls, so they run
+    /// on a blocking-pool thread via [`spawn_blocking`](tokio::task::spawn_blocking)
+    /// rather than on the async runtime thread calling this, which would
+    /// otherwise stall every other task sharing it for however long the
+    /// bus (or a stalled/non-responding PCA9685) takes to answer.
+    pub async fn read_positions(&self) -> Option<Vec<i32>> {
+        let bus = self.bus.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut guard = bus.blocking_lock();
+            let bus = guard.as_mut()?;
+
+            let mut positions = Vec::with_capacity(SERVO_COUNT_LEKIWI);
+            for channel in 0..SERVO_COUNT_LEKIWI {
+                let reg = REG_LED0_ON_L + (channel as u8) * BYTES_PER_CHANNEL;
+                let mut buf = [0u8; 4];
+                if let Err(e) = bus.block_read(reg, &mut buf) {
+                    warn!("failed to read PCA9685 channel {}: {}", channel, e);
+                    return None;
+                }
+                positions.push(decode_position(buf));
+            }
+            Some(positions)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            warn!("servo read task panicked: {}", e);
+            None
+        })
+    }
+
+    /// Writes a single channel's PWM off-count, clamped to the per-channel
+    /// bounds loaded from `config` (or [`DEFAULT_MIN_PWM`]/[`DEFAULT_MAX_PWM`]
+    /// if unconfigured).
+    pub async fn inject(
+        &self,
+        config: &ConfigStore,
+        channel: usize,
+        position: i32,
+    ) -> Result<(), String> {
+        if channel >= SERVO_COUNT_LEKIWI {
+            return Err(format!(
+                "channel {channel} out of range (0..{SERVO_COUNT_LEKIWI})"
+            ));
+        }
+
+        let min = config
+            .get(&key_min(channel))
+            .await
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MIN_PWM);
+        let max = config
+            .get(&key_max(channel))
+            .await
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_PWM);
+
+        if position < min || position > max {
+            return Err(format!(
+                "position {position} outside bounds [{min}, {max}] for channel {channel}"
+            ));
+        }
+
+        let reg = REG_LED0_ON_L + (channel as u8) * BYTES_PER_CHANNEL;
+        let buf = encode_off_count(position);
+        let bus = self.bus.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut guard = bus.blocking_lock();
+            let bus = guard
+                .as_mut()
+                .ok_or_else(|| "PCA9685 not present".to_string())?;
+            bus.block_write(reg, &buf)
+                .map_err(|e| format!("i2c write failed: {e}"))
+        })
+        .await
+        .map_err(|e| format!("servo write task panicked: {e}"))?
+    }
+}
+
+/// Encodes a PWM "off count" as the 4-byte ON/OFF register window the
+/// PCA9685 expects, with ON pinned to 0 (full-period signal starting at
+/// the top of the cycle) so a write only needs to carry one number.
+fn encode_off_count(position: i32) -> [u8; 4] {
+    let off = position as u16 & 0x0FFF;
+    [0, 0, (off & 0xFF) as u8, (off >> 8) as u8]
+}
+
+/// Decodes a channel's raw ON/OFF register window into the ON-to-OFF span
+/// `read_positions` reports, the inverse of [`encode_off_count`] (modulo
+/// the ON count, which `encode_off_count` always pins to 0).
+fn decode_position(buf: [u8; 4]) -> i32 {
+    let on = u16::from_le_bytes([buf[0], buf[1]]) & 0x0FFF;
+    let off = u16::from_le_bytes([buf[2], buf[3]]) & 0x0FFF;
+    off as i32 - on as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for position in [0, 1, 102, 512, 4095] {
+            let buf = encode_off_count(position);
+            assert_eq!(decode_position(buf), position);
+        }
+    }
+
+    #[test]
+    fn encode_masks_to_12_bits() {
+        // PCA9685 PWM registers are 12-bit; anything above 0x0FFF wraps.
+        let buf = encode_off_count(0x1000);
+        assert_eq!(decode_position(buf), 0);
+    }
+}
@@ -0,0 +1,157 @@
+//! Supervised background-worker subsystem.
+//!
+//! Borrows the task-manager design from Garage: instead of a single
+//! `tokio::spawn`'d loop that silently dies on panic, each unit of
+//! background work is a small [`Worker`] that reports its [`WorkerState`]
+//! after every step. A [`WorkerManager`] runs each worker in its own
+//! supervised task, restarting it with backoff if it panics or errors,
+//! and keeps a live status table that the `/workers` API route reads.
+
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::time;
+use tracing::{error, info, warn};
+
+/// Minimum and maximum backoff applied after a worker panics or errors.
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Result of a single [`Worker::step`] invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WorkerState {
+    /// The worker is actively doing work right now.
+    Busy,
+    /// The worker finished its step and will next run at the given time.
+    Idle(DateTime<Utc>),
+    /// The worker has permanently finished and should not be restarted.
+    Done,
+    /// The step failed; the message is surfaced through the status API.
+    Errored(String),
+}
+
+/// A unit of supervised background work.
+///
+/// Implementors perform one slice of work per [`step`](Worker::step) call;
+/// the [`WorkerManager`] decides when to call it again based on the
+/// returned [`WorkerState`].
+#[async_trait::async_trait]
+pub trait Worker: Send {
+    /// Stable, human-readable name used as the key in the status table.
+    fn name(&self) -> &str;
+
+    /// Perform one step of work and report the resulting state.
+    async fn step(&mut self) -> WorkerState;
+}
+
+/// Point-in-time status of a supervised worker, as exposed over the API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub name: String,
+    pub state: WorkerState,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<(DateTime<Utc>, String)>,
+    pub restart_count: u32,
+}
+
+/// Owns a set of named [`Worker`]s, runs each in its own supervised task,
+/// and keeps their latest [`WorkerStatus`] available for inspection.
+#[derive(Clone)]
+pub struct WorkerManager {
+    statuses: Arc<RwLock<HashMap<String, WorkerStatus>>>,
+}
+
+impl WorkerManager {
+    pub fn new() -> Self {
+        Self {
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Spawn a worker onto its own supervised task. The manager catches
+    /// panics, restarts with exponential backoff, and keeps `statuses`
+    /// up to date after every step.
+    pub fn spawn(&self, mut worker: Box<dyn Worker>) {
+        let statuses = self.statuses.clone();
+        let name = worker.name().to_string();
+
+        tokio::spawn(async move {
+            statuses.write().await.insert(
+                name.clone(),
+                WorkerStatus {
+                    name: name.clone(),
+                    state: WorkerState::Idle(Utc::now()),
+                    last_success: None,
+                    last_error: None,
+                    restart_count: 0,
+                },
+            );
+
+            let mut backoff = MIN_RESTART_BACKOFF;
+
+            loop {
+                let result = AssertUnwindSafe(worker.step()).catch_unwind().await;
+
+                let mut statuses = statuses.write().await;
+                let status = statuses
+                    .get_mut(&name)
+                    .expect("worker status inserted above");
+
+                match result {
+                    Ok(WorkerState::Done) => {
+                        info!("worker '{}' finished, not restarting", name);
+                        status.state = WorkerState::Done;
+                        return;
+                    }
+                    Ok(WorkerState::Errored(msg)) => {
+                        warn!("worker '{}' step errored: {}", name, msg);
+                        status.last_error = Some((Utc::now(), msg.clone()));
+                        status.state = WorkerState::Errored(msg);
+                        status.restart_count += 1;
+                        drop(statuses);
+                        time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+                        continue;
+                    }
+                    Ok(state @ WorkerState::Busy) | Ok(state @ WorkerState::Idle(_)) => {
+                        status.last_success = Some(Utc::now());
+                        status.state = state;
+                        backoff = MIN_RESTART_BACKOFF;
+                    }
+                    Err(panic) => {
+                        let msg = panic_message(&panic);
+                        error!("worker '{}' panicked: {}", name, msg);
+                        status.last_error = Some((Utc::now(), msg.clone()));
+                        status.state = WorkerState::Errored(format!("panic: {msg}"));
+                        status.restart_count += 1;
+                        drop(statuses);
+                        time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+                        continue;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Snapshot the current status of every supervised worker.
+    pub async fn statuses(&self) -> Vec<WorkerStatus> {
+        self.statuses.read().await.values().cloned().collect()
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}